@@ -0,0 +1,78 @@
+use ftdi_mpsse::MpsseCmdBuilder;
+
+use crate::queue::Queue;
+
+//With the /5 predivider enabled the MPSSE clock is derived from a 12 MHz
+//reference (6 MHz max TCK); disabling it switches to the FT2232H/FT4232H's
+//native 60 MHz reference (30 MHz max TCK).
+const BASE_CLOCK_DIV5_HZ: u64 = 12_000_000;
+const BASE_CLOCK_DIV5_DISABLED_HZ: u64 = 60_000_000;
+
+/// Enable or disable RTCK-based adaptive clocking: the FTDI chip waits for
+/// the target to return its own TCK on each edge instead of free-running,
+/// so the controller can track a target whose JTAG clock is gated or
+/// divided from a core clock (common on some ARM debug ports).
+pub fn set_adaptive_clock(queue: &mut Queue, enable: bool){
+    let cmd = if enable {
+        MpsseCmdBuilder::new().enable_adaptive_data_clocking()
+    } else {
+        MpsseCmdBuilder::new().disable_adaptive_data_clocking()
+    };
+    queue.queue(&cmd, 0);
+}
+
+/// Queue a `set_clock` for a TCK that does not exceed `khz`, using the /5
+/// domain up to its 6 MHz ceiling and the /5-disabled domain above it, and
+/// return the TCK frequency (in kHz) that was actually selected. Only that
+/// one domain is tried, even where the other would land closer to `khz`.
+pub fn set_speed_khz(queue: &mut Queue, khz: u32) -> u32 {
+    let target_hz = khz as u64 * 1000;
+
+    let (base_hz, div5_disabled) = if target_hz > BASE_CLOCK_DIV5_HZ / 2 {
+        (BASE_CLOCK_DIV5_DISABLED_HZ, true)
+    } else {
+        (BASE_CLOCK_DIV5_HZ, false)
+    };
+
+    //Round the divisor up so the selected TCK never exceeds the requested
+    //speed - flooring it here could overshoot a target's speed ceiling.
+    let divisor = base_hz.div_ceil(2 * target_hz).saturating_sub(1).min(0xffff) as u16;
+    let actual_hz = base_hz / (2 * (divisor as u64 + 1));
+
+    let cmd = MpsseCmdBuilder::new().set_clock(divisor.into(), Some(div5_disabled));
+    queue.queue(&cmd, 0);
+
+    (actual_hz / 1000) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_exceeds_requested_speed() {
+        //350 kHz doesn't evenly divide either clock domain, so the picked
+        //divisor must round up rather than down to avoid overshooting.
+        let mut queue = Queue::new();
+        let actual = set_speed_khz(&mut queue, 350);
+        assert!(actual <= 350, "actual {} kHz exceeds requested 350 kHz", actual);
+    }
+
+    #[test]
+    fn exact_divisor_is_unaffected_by_rounding() {
+        //6 MHz / (2 * 1) = 3 MHz is an exact divisor in the /5 domain
+        let mut queue = Queue::new();
+        let actual = set_speed_khz(&mut queue, 3000);
+        assert_eq!(actual, 3000);
+    }
+
+    #[test]
+    fn picks_faster_domain_above_div5_ceiling() {
+        //Above 6 MHz max TCK for the /5 domain the /5-disabled 60 MHz
+        //reference must be selected instead.
+        let mut queue = Queue::new();
+        let actual = set_speed_khz(&mut queue, 10_000);
+        assert!(actual <= 10_000);
+        assert!(actual > 6_000);
+    }
+}
@@ -1,15 +1,35 @@
+mod tap;
+mod swd;
+mod signal;
+mod scan;
+mod queue;
+mod clock;
+
 use std::time::Duration;
 use std::thread;
 
 use libftd2xx::{Ftdi, FtdiCommon, BitMode};
 use ftdi_mpsse::MpsseCmdBuilder;
 
-//Instructions
+use tap::{Tap, TapState};
+use swd::Transport;
+use signal::Signals;
+use queue::Queue;
+use clock::{set_adaptive_clock, set_speed_khz};
+
+//Instructions - not yet wired up to the scan-chain based IR/DR shifting,
+//kept here as the reference opcode table for whoever adds that next
+#[allow(dead_code)]
 const IDCODE:   u8 = 0x09;
+#[allow(dead_code)]
 const USER1:    u8 = 0x02;
+#[allow(dead_code)]
 const USER2:    u8 = 0x03;
+#[allow(dead_code)]
 const USER3:    u8 = 0x22;
+#[allow(dead_code)]
 const USER4:    u8 = 0x23;
+#[allow(dead_code)]
 const USERCODE: u8 = 0x08;
 
 fn wait_data(ft: &mut Ftdi){
@@ -18,9 +38,6 @@ fn wait_data(ft: &mut Ftdi){
 
         if queue_status != 0 {break}
 
-        let status = ft.status().unwrap();
-
-        println!("Data wait: status: {:?}", status);
         println!("Data wait: looping: {}", queue_status);
 
         thread::sleep(Duration::from_millis(10));
@@ -30,121 +47,17 @@ fn wait_data(ft: &mut Ftdi){
 fn sync(ft: &mut Ftdi){
     //Send a bad command to sync
     let bad_command: [u8; 1] = [0xaa; 1];
-    ft.write_all(&bad_command).unwrap();
+    ft.write(&bad_command).unwrap();
 
     wait_data(ft);
 
     let mut buf: [u8; 2] = [0; 2];
-    ft.read_all(&mut buf).unwrap();
+    ft.read(&mut buf).unwrap();
 
     assert_eq!(buf[0], 0xfa);
     assert_eq!(buf[1], 0xaa);
 }
 
-//Ensure the TAP state machine is in the reset state
-fn reset_tap(ft: &mut Ftdi){
-    let cmd 
-        = MpsseCmdBuilder::new()
-        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0x7f, false, 7);
-
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-
-//Shift instruction
-//Ends in the Exit IR state
-fn shift_ir(ft: &mut Ftdi, insn: u8, len: u8){
-    assert!(len >= 2);
-
-    //Shift in the IR
-    //IR length is 6 bits
-    let cmd = MpsseCmdBuilder::new()
-        .clock_bits_out(ftdi_mpsse::ClockBitsOut::LsbNeg, insn, len - 1);
-    ft.write_all(cmd.as_slice()).unwrap();
-
-    //Shift the final instruction bit
-    //Transition to Exit IR (1)
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0x01, false, 1);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-//Shift data register
-//Ends in Exit DR
-fn shift_dr(ft: &mut Ftdi, data: u8, len: u8){
-    assert!(len >= 2);
-
-    //Shift in the DR
-    //8 Bits, all 0s
-    let cmd = MpsseCmdBuilder::new()
-        .clock_bits(ftdi_mpsse::ClockBits::LsbPosIn, data, 7);
-    ft.write_all(cmd.as_slice()).unwrap();
-
-    //Shift the final bit
-    //Transition to Exit DR (1)
-    //TODO: set last data bit
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms(ftdi_mpsse::ClockTMS::NegTMSPosTDO, 0x01, false, 1);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-fn shift_bytes(ft: &mut Ftdi, data: &[u8]){
-    assert!(data.len() >= 2);
-
-    let (last, init) = data.split_last().unwrap();
-
-    //Shift in the DR
-    //8 Bits, all 0s
-    let cmd = MpsseCmdBuilder::new()
-        .clock_data(ftdi_mpsse::ClockData::LsbPosIn, init);
-    ft.write_all(cmd.as_slice()).unwrap();
-
-    //Shift in the DR
-    let cmd = MpsseCmdBuilder::new()
-        .clock_bits(ftdi_mpsse::ClockBits::LsbPosIn, *last, 7);
-    ft.write_all(cmd.as_slice()).unwrap();
-
-    //Shift the final bit
-    //Transition to Exit DR (1)
-    //TODO: set last data bit
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms(ftdi_mpsse::ClockTMS::NegTMSPosTDO, 0x01, false, 1);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-fn reset_to_shift_dr(ft: &mut Ftdi) {
-    //Get from reset to shift DR
-    //Reset -0-> Idle -1-> DR scan -0-> Capture DR -0-> Shift DR
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0x2, false, 4);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-fn reset_to_shift_ir(ft: &mut Ftdi){
-    //Get from reset to shift IR
-    //Reset -0-> Idle -1-> DR scan -1-> IR scan -0-> Capture IR -0-> Shift IR
-    //Initial transition to reset seems unnecessary
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0x6, false, 5);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-fn exit_ir_to_shift_dr(ft: &mut Ftdi){
-    //Get to shift DR
-    //Exit IR -1-> Update IR -1-> DR Scan -0-> Capture DR -0-> Shift DR
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0x03, false, 4);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
-fn exit_dr_to_reset(ft: &mut Ftdi){
-    //Back to TAP reset
-    //Exit DR -1-> Update DR -1-> Select DR -1-> Select IR -1-> Reset
-    let cmd = MpsseCmdBuilder::new()
-        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0xff, false, 4);
-    ft.write_all(cmd.as_slice()).unwrap();
-}
-
 fn main() {
     let mut ft = Ftdi::new().unwrap();
 
@@ -159,9 +72,6 @@ fn main() {
     ft.reset().unwrap();
 
     //Debug
-    let status = ft.status().unwrap();
-    println!("Status: {:?}", status);
-
     let queue_status = ft.queue_status().unwrap();
     println!("Queue status: {:?}", queue_status);
 
@@ -177,43 +87,75 @@ fn main() {
 
     sync(&mut ft);
 
-    //JTAG setup
-    let cmd = MpsseCmdBuilder::new()
-        .set_clock(0x5db, Some(false))
-        .disable_adaptive_data_clocking()
-        .disable_3phase_data_clocking()
-        .disable_loopback();
+    //Commands from here on are queued up and flushed together in one USB
+    //transfer rather than written one at a time.
+    let mut queue = Queue::new();
 
-    println!("{:x?}", cmd.as_slice());
-    ft.write_all(cmd.as_slice()).unwrap();
+    //JTAG setup: a conservative default speed, adaptive clocking off until
+    //a caller opts in for a target that needs RTCK
+    let khz = set_speed_khz(&mut queue, 1000);
+    println!("TCK set to {} kHz", khz);
+    set_adaptive_clock(&mut queue, false);
 
-    //Port direction and initial values
     let cmd = MpsseCmdBuilder::new()
-        .set_gpio_lower(0x08, 0x0b)
-        .set_gpio_upper(0x00, 0x00);
-
-    println!("{:x?}", cmd.as_slice());
-    ft.write_all(cmd.as_slice()).unwrap();
-
-    reset_tap(&mut ft);
-
-    reset_to_shift_ir(&mut ft);
-    //reset_to_shift_dr(&mut ft);
-
-    shift_ir(&mut ft, IDCODE, 6);
-
-    exit_ir_to_shift_dr(&mut ft);
-
-    //shift_dr(&mut ft, 0, 8);
-    shift_bytes(&mut ft, &[0, 0, 0, 0]);
-
-    exit_dr_to_reset(&mut ft);
-
-    //Read back
-    wait_data(&mut ft);
-
-    let mut buf: [u8; 5] = [0; 5];
-    ft.read_all(&mut buf).unwrap();
-
-    println!("{:x?}", buf);
+        .disable_3phase_data_clocking()
+        .disable_loopback();
+    queue.queue(&cmd, 0);
+
+    //Port direction and initial values, declared per signal rather than as
+    //raw masks so board wiring (reset lines, LEDs) is easy to retarget
+    let mut signals = Signals::new();
+    signals.define_signal("TCK",   0x0001, 0x0001, false, false);
+    signals.define_signal("TDI",   0x0002, 0x0002, false, false);
+    signals.define_signal("TMS",   0x0008, 0x0008, false, false);
+    signals.define_signal("nTRST", 0x0100, 0x0100, true,  true);
+    signals.define_signal("nSRST", 0x0200, 0x0200, true,  true);
+    signals.define_signal("LED",   0x0400, 0x0400, false, false);
+
+    signals.set_signal(&mut queue, "TCK", false);
+    signals.set_signal(&mut queue, "TDI", false);
+    signals.set_signal(&mut queue, "TMS", true);
+    signals.set_signal(&mut queue, "nTRST", false);
+    signals.set_signal(&mut queue, "nSRST", false);
+    signals.set_signal(&mut queue, "LED", false);
+
+    let mut tap = Tap::new();
+    tap.move_to(&mut queue, TapState::TestLogicReset);
+    queue.flush(&mut ft);
+
+    //`--swd` switches the wire protocol to SWD instead of scanning the
+    //JTAG chain; `--dormant` additionally wakes the target up from the
+    //dormant state first (for multi-drop targets that power up dormant).
+    let args: Vec<String> = std::env::args().collect();
+    let transport = if args.iter().any(|a| a == "--swd") { Transport::Swd } else { Transport::Jtag };
+
+    match transport {
+        Transport::Jtag => {
+            let idcodes = scan::scan_chain(&mut ft, &mut tap);
+            println!("Scan chain IDCODEs: {:x?}", idcodes);
+        }
+        Transport::Swd => {
+            if args.iter().any(|a| a == "--dormant") {
+                swd::dormant_to_swd(&mut ft);
+            } else {
+                swd::jtag_to_swd(&mut ft);
+            }
+
+            //DPIDR: read-only, auto-exposed by the target right after line reset
+            match swd::swd_read(&mut ft, false, 0x00) {
+                Ok(dpidr) => println!("SWD DPIDR: {:#010x}", dpidr),
+                Err(swd::SwdError::Ack(ack)) => println!("SWD read failed: ACK={:#05b}", ack),
+                Err(swd::SwdError::Parity) => println!("SWD read failed: parity error"),
+            }
+
+            //Clear any sticky error flags left over from the read above
+            match swd::swd_write(&mut ft, false, 0x00, 0x1e) {
+                Ok(()) => {}
+                Err(swd::SwdError::Ack(ack)) => println!("SWD write failed: ACK={:#05b}", ack),
+                Err(swd::SwdError::Parity) => println!("SWD write failed: parity error"),
+            }
+
+            swd::swd_to_jtag(&mut ft);
+        }
+    }
 }
@@ -0,0 +1,56 @@
+use libftd2xx::{Ftdi, FtdiCommon};
+use ftdi_mpsse::MpsseCmdBuilder;
+
+//Matches the ft.set_usb_parameters(16384) call in the setup incantation
+const USB_TRANSFER_SIZE: usize = 16384;
+
+//MPSSE clock_data/clock_data_in commands encode their length in 16 bits
+pub const MAX_CLOCK_DATA_LEN: usize = 65536;
+
+/// Accumulates MPSSE commands so a whole sequence of shifts/moves can be
+/// sent to the device in one USB transfer instead of the round-trip-per-
+/// helper-call the driver used to pay for each TMS/TDI clock. Mirrors how
+/// OpenOCD's mpsse layer defers commands and coalesces them before a
+/// single `mpsse_flush`.
+pub struct Queue {
+    buf: Vec<u8>,
+    expected_reads: usize,
+}
+
+impl Queue {
+    pub fn new() -> Self {
+        Queue { buf: Vec::new(), expected_reads: 0 }
+    }
+
+    /// Append a command's bytes, noting how many bytes of TDO/GPIO
+    /// readback it will produce once flushed.
+    pub fn queue(&mut self, cmd: &MpsseCmdBuilder, reads: usize){
+        self.buf.extend_from_slice(cmd.as_slice());
+        self.expected_reads += reads;
+    }
+
+    /// Write everything accumulated so far, chunked to the device's USB
+    /// transfer size, then read back exactly as many bytes as were queued.
+    pub fn flush(&mut self, ft: &mut Ftdi) -> Vec<u8> {
+        for chunk in self.buf.chunks(USB_TRANSFER_SIZE) {
+            ft.write(chunk).unwrap();
+        }
+        self.buf.clear();
+
+        let mut data = vec![0u8; self.expected_reads];
+        if self.expected_reads > 0 {
+            ft.read(&mut data).unwrap();
+        }
+        self.expected_reads = 0;
+
+        data
+    }
+
+    /// Number of command bytes queued so far but not yet flushed. Only
+    /// meant for tests that need to observe whether a call queued
+    /// anything without a real device to flush to.
+    #[cfg(test)]
+    pub(crate) fn queued_len(&self) -> usize {
+        self.buf.len()
+    }
+}
@@ -0,0 +1,245 @@
+use libftd2xx::Ftdi;
+use ftdi_mpsse::MpsseCmdBuilder;
+
+use crate::tap::{Tap, TapState};
+use crate::queue::{Queue, MAX_CLOCK_DATA_LEN};
+
+/// A small owned bit string, LSB (first shifted) at index 0. The crate has
+/// no need for a general-purpose bitvec dependency, so this just wraps the
+/// handful of operations scan_dr needs.
+#[derive(Debug, Clone, Default)]
+pub struct BitVec {
+    bits: Vec<bool>,
+}
+
+impl BitVec {
+    pub fn new() -> Self {
+        BitVec { bits: Vec::new() }
+    }
+
+    pub fn push(&mut self, bit: bool){
+        self.bits.push(bit);
+    }
+
+    pub fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        self.bits[i]
+    }
+
+    /// Interpret the first 32 bits (LSB first) as a little-endian word.
+    pub fn to_u32(&self) -> u32 {
+        let mut value = 0u32;
+        for (i, bit) in self.bits.iter().take(32).enumerate() {
+            if *bit {
+                value |= 1 << i;
+            }
+        }
+        value
+    }
+
+    /// All ones, `len` bits long - used to force every TAP into BYPASS.
+    pub fn ones(len: usize) -> Self {
+        BitVec { bits: vec![true; len] }
+    }
+
+    /// Pack `n` bits starting at `start` (LSB first) into a byte, for the
+    /// `clock_data`/`clock_bits` calls that clock whole bytes at a time.
+    fn pack_byte(&self, start: usize, n: usize) -> u8 {
+        let mut byte = 0u8;
+        for i in 0..n {
+            if self.get(start + i) {
+                byte |= 1 << i;
+            }
+        }
+        byte
+    }
+}
+
+//Long enough to cover any plausible total IR length in a real scan chain
+const BYPASS_IR_LEN: usize = 64;
+
+//Longest chain the walking 1 measurement can detect: the marker is
+//followed by this many zeros, so it only reappears within the scan for
+//chains of up to MAX_DEVICES devices
+const MAX_DEVICES: usize = 32;
+
+/// Shift `tdi_bits` (length `length`) into the current scan register and
+/// return the bits captured on TDO.
+///
+/// Clocks full bytes via `clock_data`, the remaining bits (< 8) via
+/// `clock_bits`, and the final bit via `clock_tms` so the TAP leaves
+/// Shift-DR/IR on the same clock that captures the last TDO bit. The
+/// whole sequence is queued and flushed in a single transfer, so this can
+/// be preceded by queued-but-unflushed `Tap::move_to` calls at no extra
+/// USB round trip.
+pub fn scan_dr(ft: &mut Ftdi, queue: &mut Queue, tap: &mut Tap, tdi_bits: &BitVec, length: usize) -> BitVec {
+    assert!(length >= 1);
+
+    let shifting_ir = tap.current_state() == TapState::ShiftIr;
+    assert!(shifting_ir || tap.current_state() == TapState::ShiftDr);
+
+    let full_bytes = (length - 1) / 8;
+    let leftover_bits = (length - 1) % 8;
+
+    let mut bit_idx = 0;
+
+    if full_bytes > 0 {
+        let bytes: Vec<u8> = (0..full_bytes).map(|_| {
+            let byte = tdi_bits.pack_byte(bit_idx, 8);
+            bit_idx += 8;
+            byte
+        }).collect();
+
+        //Honour the MPSSE clock_data length limit for pathologically long scans
+        for chunk in bytes.chunks(MAX_CLOCK_DATA_LEN) {
+            let cmd = MpsseCmdBuilder::new()
+                .clock_data(ftdi_mpsse::ClockData::LsbPosIn, chunk);
+            queue.queue(&cmd, chunk.len());
+        }
+    }
+
+    if leftover_bits > 0 {
+        let byte = tdi_bits.pack_byte(bit_idx, leftover_bits);
+
+        let cmd = MpsseCmdBuilder::new()
+            .clock_bits(ftdi_mpsse::ClockBits::LsbPosIn, byte, leftover_bits as u8);
+        queue.queue(&cmd, 1);
+    }
+
+    //Final bit: clock on TMS=1 so the TAP moves to Exit1, capturing the
+    //last TDO bit on the same edge
+    let last_tdi = tdi_bits.get(length - 1);
+    let cmd = MpsseCmdBuilder::new()
+        .clock_tms(ftdi_mpsse::ClockTMS::NegTMSPosTDO, 0x01, last_tdi, 1);
+    queue.queue(&cmd, 1);
+
+    let captured = queue.flush(ft);
+    let mut pos = 0;
+    let mut tdo = BitVec::new();
+
+    for &byte in &captured[pos..pos + full_bytes] {
+        for i in 0..8 {
+            tdo.push((byte >> i) & 1 != 0);
+        }
+    }
+    pos += full_bytes;
+
+    if leftover_bits > 0 {
+        let byte = captured[pos];
+        pos += 1;
+        for i in 0..leftover_bits {
+            tdo.push((byte >> (8 - leftover_bits + i)) & 1 != 0);
+        }
+    }
+
+    tdo.push((captured[pos] >> 7) & 1 != 0);
+
+    tap.set_state(if shifting_ir { TapState::Exit1Ir } else { TapState::Exit1Dr });
+
+    tdo
+}
+
+/// Enumerate the devices on the JTAG scan chain and return each one's 32
+/// bit IDCODE, in chain order (closest to TDO first).
+///
+/// First forces every TAP into BYPASS and measures the chain length by
+/// timing a walking 1 through the 1 bit BYPASS registers, then resets the
+/// chain (which auto-loads IDCODE, where present, into each DR) and shifts
+/// out one 32 bit word per device.
+pub fn scan_chain(ft: &mut Ftdi, tap: &mut Tap) -> Vec<u32> {
+    let mut queue = Queue::new();
+
+    //Force every device into BYPASS
+    tap.move_to(&mut queue, TapState::ShiftIr);
+    scan_dr(ft, &mut queue, tap, &BitVec::ones(BYPASS_IR_LEN), BYPASS_IR_LEN);
+    tap.move_to(&mut queue, TapState::RunTestIdle);
+
+    //Measure the chain length: shift a single 1 followed by zeros through
+    //the BYPASS registers and count how many clocks until it reappears
+    tap.move_to(&mut queue, TapState::ShiftDr);
+
+    let mut marker = BitVec::new();
+    marker.push(true);
+    for _ in 0..MAX_DEVICES {
+        marker.push(false);
+    }
+
+    let captured = scan_dr(ft, &mut queue, tap, &marker, marker.len());
+    tap.move_to(&mut queue, TapState::RunTestIdle);
+
+    let device_count = (1..captured.len())
+        .find(|&i| captured.get(i))
+        .unwrap_or(0);
+
+    //Reset loads each TAP's IDCODE (or forces BYPASS) into its DR
+    tap.move_to(&mut queue, TapState::TestLogicReset);
+    tap.move_to(&mut queue, TapState::ShiftDr);
+
+    let mut idcodes = Vec::new();
+    for _ in 0..device_count.max(1) {
+        let word = scan_dr(ft, &mut queue, tap, &BitVec::ones(32), 32);
+        tap.move_to(&mut queue, TapState::ShiftDr);
+
+        //Bit 0 clear means this TAP has no IDCODE (BYPASS instead)
+        if !word.get(0) {
+            break;
+        }
+
+        idcodes.push(word.to_u32());
+    }
+
+    tap.move_to(&mut queue, TapState::TestLogicReset);
+    queue.flush(ft);
+
+    idcodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_u32_round_trips_le_bits() {
+        let mut bits = BitVec::new();
+        for i in 0..32 {
+            bits.push((0xdeadbeefu32 >> i) & 1 != 0);
+        }
+        assert_eq!(bits.to_u32(), 0xdeadbeef);
+    }
+
+    #[test]
+    fn ones_are_all_set() {
+        let bits = BitVec::ones(5);
+        assert_eq!(bits.len(), 5);
+        for i in 0..5 {
+            assert!(bits.get(i));
+        }
+    }
+
+    #[test]
+    fn pack_byte_is_lsb_first() {
+        let mut bits = BitVec::new();
+        for b in [true, false, true, true, false, false, false, false] {
+            bits.push(b);
+        }
+        //bit0=1, bit2=1, bit3=1 -> 0b0000_1101
+        assert_eq!(bits.pack_byte(0, 8), 0b0000_1101);
+    }
+
+    #[test]
+    fn pack_byte_partial_length_matches_bit_count() {
+        let mut bits = BitVec::new();
+        for b in [true, true, false, false, false, false, true] {
+            bits.push(b);
+        }
+        //A 7 bit leftover chunk (e.g. from a 64 bit BYPASS scan) must clock
+        //exactly 7 bits, not 6 - dropping a bit here corrupts every scan
+        //whose trailing chunk isn't a full byte. The 7th bit is set so a
+        //6 bit pack would give 0b0000_0011 instead.
+        assert_eq!(bits.len(), 7);
+        assert_eq!(bits.pack_byte(0, 7), 0b0100_0011);
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use ftdi_mpsse::MpsseCmdBuilder;
+
+use crate::queue::Queue;
+
+/// A named GPIO line on the FTDI's lower+upper 16 bit port, e.g. nTRST,
+/// nSRST or a status LED. Mirrors OpenOCD's ftdi layer signal abstraction
+/// so board wiring can be declared instead of hand-coded as raw hex masks.
+#[derive(Debug, Clone)]
+pub struct Signal {
+    //Bits in the 16 bit GPIO word this signal drives
+    pub data_mask: u16,
+    //Bits in the 16 bit direction word this signal owns (set = output)
+    pub oe_mask: u16,
+    //True if the signal is active low
+    pub inverted: bool,
+    //True if the pin is open-drain (e.g. a shared nTRST/nSRST line): only
+    //ever driven low, released (tri-stated) otherwise so a board pull-up
+    //can bring it high without contention from another driver
+    pub open_drain: bool,
+}
+
+/// Registry of named signals sharing the FTDI's 16 GPIO pins, with the
+/// combined output/direction state persisted across calls so that setting
+/// one signal doesn't clobber another.
+pub struct Signals {
+    signals: HashMap<String, Signal>,
+    output: u16,
+    direction: u16,
+}
+
+impl Signals {
+    pub fn new() -> Self {
+        Signals { signals: HashMap::new(), output: 0, direction: 0 }
+    }
+
+    pub fn define_signal(&mut self, name: &str, data_mask: u16, oe_mask: u16, inverted: bool, open_drain: bool){
+        self.signals.insert(name.to_string(), Signal {
+            data_mask,
+            oe_mask,
+            inverted,
+            open_drain,
+        });
+    }
+
+    /// Drive `name` to `value`, composing it with whatever other signals
+    /// currently hold, then queue the combined output/direction words.
+    /// Callers flush `queue` once they need the pins actually updated.
+    pub fn set_signal(&mut self, queue: &mut Queue, name: &str, value: bool){
+        let signal = self.signals.get(name)
+            .unwrap_or_else(|| panic!("undefined signal: {}", name));
+
+        let value = value ^ signal.inverted;
+
+        if value {
+            self.output |= signal.data_mask;
+        } else {
+            self.output &= !signal.data_mask;
+        }
+
+        //Push-pull pins are always driven as outputs once defined. Open-
+        //drain pins are only driven (output) while asserting their low
+        //level; otherwise they're released (input) so a board pull-up
+        //can bring the line high without two drivers fighting it.
+        if signal.open_drain {
+            if value {
+                self.direction &= !signal.oe_mask;
+            } else {
+                self.direction |= signal.oe_mask;
+            }
+        } else {
+            self.direction |= signal.oe_mask;
+        }
+
+        let cmd = MpsseCmdBuilder::new()
+            .set_gpio_lower((self.output & 0xff) as u8, (self.direction & 0xff) as u8)
+            .set_gpio_upper((self.output >> 8) as u8, (self.direction >> 8) as u8);
+        queue.queue(&cmd, 0);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn state(&self) -> (u16, u16) {
+        (self.output, self.direction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_pull_stays_driven_both_levels() {
+        let mut signals = Signals::new();
+        let mut queue = Queue::new();
+        signals.define_signal("LED", 0x0400, 0x0400, false, false);
+
+        signals.set_signal(&mut queue, "LED", true);
+        assert_eq!(signals.state(), (0x0400, 0x0400));
+
+        signals.set_signal(&mut queue, "LED", false);
+        assert_eq!(signals.state(), (0x0000, 0x0400));
+    }
+
+    #[test]
+    fn open_drain_releases_direction_when_inactive() {
+        let mut signals = Signals::new();
+        let mut queue = Queue::new();
+        //Active low, open-drain: asserting (true) drives the pin low
+        signals.define_signal("nTRST", 0x0100, 0x0100, true, true);
+
+        signals.set_signal(&mut queue, "nTRST", true);
+        let (output, direction) = signals.state();
+        assert_eq!(output & 0x0100, 0); //driven low
+        assert_eq!(direction & 0x0100, 0x0100); //output enabled to drive it
+
+        signals.set_signal(&mut queue, "nTRST", false);
+        let (_, direction) = signals.state();
+        assert_eq!(direction & 0x0100, 0); //released, board pull-up takes over
+    }
+
+    #[test]
+    fn setting_one_signal_preserves_others() {
+        let mut signals = Signals::new();
+        let mut queue = Queue::new();
+        signals.define_signal("TMS", 0x0008, 0x0008, false, false);
+        signals.define_signal("nTRST", 0x0100, 0x0100, true, true);
+
+        signals.set_signal(&mut queue, "TMS", true);
+        signals.set_signal(&mut queue, "nTRST", true);
+
+        let (output, direction) = signals.state();
+        assert_eq!(output & 0x0008, 0x0008); //TMS still high
+        assert_eq!(direction & 0x0008, 0x0008); //TMS still an output
+    }
+}
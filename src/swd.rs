@@ -0,0 +1,232 @@
+use libftd2xx::Ftdi;
+use ftdi_mpsse::MpsseCmdBuilder;
+
+use crate::queue::Queue;
+
+/// Which debug transport is currently selected on the wire.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Jtag,
+    Swd,
+}
+
+//JTAG->SWD and SWD->JTAG 16 bit magic switch sequences, sent LSB first
+const JTAG_TO_SWD: u16 = 0xe79e;
+const SWD_TO_JTAG: u16 = 0xe73c;
+
+//128 bit JTAG-to-dormant selection sequence (ADIv5.2 B5.1), sent LSB first
+//in 16 bit words, low word first
+const JTAG_TO_DORMANT: [u16; 8] = [
+    0xb9a6, 0x33f7, 0xa10e, 0x19a0,
+    0xf1ff, 0x0f2f, 0x09f7, 0x29b4,
+];
+
+//Dormant -> SWD activation code, sent after the dormant-select sequence
+const DORMANT_TO_SWD: u8 = 0x1a;
+
+//ADBUS3 carries TMS in JTAG mode and SWDIO in SWD mode. The other output
+//pins (ADBUS0 = TCK/SWCLK, ADBUS1 = TDI) stay as configured for JTAG.
+const SWDIO_BIT: u8 = 0x08;
+const SWD_DIR_OUT: u8 = 0x0b;
+const SWD_DIR_IN: u8 = 0x03;
+
+fn swdio_direction(queue: &mut Queue, output: bool){
+    let dir = if output { SWD_DIR_OUT } else { SWD_DIR_IN };
+    let val = if output { SWDIO_BIT } else { 0x00 };
+
+    let cmd = MpsseCmdBuilder::new().set_gpio_lower(val, dir);
+    queue.queue(&cmd, 0);
+}
+
+//Queue `len` bits of `bits` out on TMS/SWDIO (LSB first), chunked into
+//groups of at most 7 (the MPSSE clock_tms_out limit)
+fn clock_tms_bits(queue: &mut Queue, bits: u32, len: u32){
+    let mut bits = bits;
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let chunk_len = remaining.min(7);
+        let chunk_bits = (bits & ((1 << chunk_len) - 1)) as u8;
+
+        let cmd = MpsseCmdBuilder::new()
+            .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, chunk_bits, false, chunk_len as u8);
+        queue.queue(&cmd, 0);
+
+        bits >>= chunk_len;
+        remaining -= chunk_len;
+    }
+}
+
+fn line_reset(queue: &mut Queue){
+    //>=50 TCK cycles with SWDIO/TMS high (8 x 7 = 56)
+    let cmd = MpsseCmdBuilder::new()
+        .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, 0x7f, false, 7);
+    for _ in 0..8 {
+        queue.queue(&cmd, 0);
+    }
+}
+
+fn idle_cycles(queue: &mut Queue, count: u8){
+    clock_tms_bits(queue, 0, count as u32);
+}
+
+/// Switch the wire protocol from JTAG to SWD.
+pub fn jtag_to_swd(ft: &mut Ftdi){
+    let mut queue = Queue::new();
+    line_reset(&mut queue);
+    clock_tms_bits(&mut queue, JTAG_TO_SWD as u32, 16);
+    line_reset(&mut queue);
+    idle_cycles(&mut queue, 2);
+    queue.flush(ft);
+}
+
+/// Switch the wire protocol from SWD back to JTAG.
+pub fn swd_to_jtag(ft: &mut Ftdi){
+    let mut queue = Queue::new();
+    line_reset(&mut queue);
+    clock_tms_bits(&mut queue, SWD_TO_JTAG as u32, 16);
+    line_reset(&mut queue);
+    idle_cycles(&mut queue, 2);
+    queue.flush(ft);
+}
+
+/// Select SWD out of the dormant state, for multi-drop targets that power
+/// up dormant rather than directly in JTAG or SWD mode.
+pub fn dormant_to_swd(ft: &mut Ftdi){
+    let mut queue = Queue::new();
+    line_reset(&mut queue);
+
+    for word in JTAG_TO_DORMANT.iter() {
+        clock_tms_bits(&mut queue, *word as u32, 16);
+    }
+
+    line_reset(&mut queue);
+    clock_tms_bits(&mut queue, DORMANT_TO_SWD as u32, 8);
+    idle_cycles(&mut queue, 2);
+    queue.flush(ft);
+}
+
+#[derive(Debug)]
+pub enum SwdError {
+    /// The target returned something other than OK (1) for the ACK phase
+    Ack(u8),
+    /// The 32 bit read data failed its trailing parity bit
+    Parity,
+}
+
+fn request_header(ap_not_dp: bool, read: bool, addr: u8) -> u8 {
+    let a23 = (addr >> 2) & 0x3;
+    let parity = (ap_not_dp as u8) ^ (read as u8) ^ (a23.count_ones() as u8 & 1);
+
+    let mut header = 0x01; //start bit
+    header |= (ap_not_dp as u8) << 1;
+    header |= (read as u8) << 2;
+    header |= a23 << 3;
+    header |= (parity & 1) << 5;
+    header |= 1 << 7; //park bit
+    header
+}
+
+/// Read a 32 bit word from the target over SWD, selecting either the debug
+/// port (`ap_not_dp = false`) or an access port register.
+pub fn swd_read(ft: &mut Ftdi, ap_not_dp: bool, addr: u8) -> Result<u32, SwdError> {
+    let header = request_header(ap_not_dp, true, addr);
+
+    let mut queue = Queue::new();
+    swdio_direction(&mut queue, true);
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits_out(ftdi_mpsse::ClockBitsOut::LsbNeg, header, 8);
+    queue.queue(&cmd, 0);
+
+    //Turnaround: host releases SWDIO before the target drives ACK. One
+    //dummy clock for the turnaround cycle itself, then the 3 ACK bits.
+    swdio_direction(&mut queue, false);
+
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits(ftdi_mpsse::ClockBits::LsbPosIn, 0x00, 4);
+    queue.queue(&cmd, 1);
+
+    let ack_byte = queue.flush(ft);
+    let ack = (ack_byte[0] >> 5) & 0x7;
+
+    if ack != 0x1 {
+        //Turnaround back to host-driven before bailing out
+        let mut queue = Queue::new();
+        swdio_direction(&mut queue, true);
+        queue.flush(ft);
+        return Err(SwdError::Ack(ack));
+    }
+
+    let mut queue = Queue::new();
+    let cmd = MpsseCmdBuilder::new()
+        .clock_data_in(ftdi_mpsse::ClockDataIn::LsbPos, 4);
+    queue.queue(&cmd, 4);
+
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits(ftdi_mpsse::ClockBits::LsbPosIn, 0x00, 1);
+    queue.queue(&cmd, 1);
+
+    let data = queue.flush(ft);
+    let value = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+    let parity_bit = (data[4] >> 7) & 1;
+
+    let mut queue = Queue::new();
+    swdio_direction(&mut queue, true);
+    idle_cycles(&mut queue, 8);
+    queue.flush(ft);
+
+    if (value.count_ones() as u8 & 1) != parity_bit {
+        return Err(SwdError::Parity);
+    }
+
+    Ok(value)
+}
+
+/// Write a 32 bit word to the target over SWD.
+pub fn swd_write(ft: &mut Ftdi, ap_not_dp: bool, addr: u8, data: u32) -> Result<(), SwdError> {
+    let header = request_header(ap_not_dp, false, addr);
+
+    let mut queue = Queue::new();
+    swdio_direction(&mut queue, true);
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits_out(ftdi_mpsse::ClockBitsOut::LsbNeg, header, 8);
+    queue.queue(&cmd, 0);
+
+    //Turnaround: one dummy clock for the turnaround cycle itself, then
+    //the 3 ACK bits.
+    swdio_direction(&mut queue, false);
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits(ftdi_mpsse::ClockBits::LsbPosIn, 0x00, 4);
+    queue.queue(&cmd, 1);
+
+    let ack_byte = queue.flush(ft);
+    let ack = (ack_byte[0] >> 5) & 0x7;
+
+    //Turnaround again: one dummy clock while the target releases SWDIO,
+    //then the host drives the data phase
+    let mut queue = Queue::new();
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits_out(ftdi_mpsse::ClockBitsOut::LsbNeg, 0x00, 1);
+    queue.queue(&cmd, 0);
+    swdio_direction(&mut queue, true);
+
+    if ack != 0x1 {
+        queue.flush(ft);
+        return Err(SwdError::Ack(ack));
+    }
+
+    let bytes = data.to_le_bytes();
+    let cmd = MpsseCmdBuilder::new()
+        .clock_data_out(ftdi_mpsse::ClockDataOut::LsbNeg, &bytes);
+    queue.queue(&cmd, 0);
+
+    let parity = data.count_ones() as u8 & 1;
+    let cmd = MpsseCmdBuilder::new()
+        .clock_bits_out(ftdi_mpsse::ClockBitsOut::LsbNeg, parity, 1);
+    queue.queue(&cmd, 0);
+
+    idle_cycles(&mut queue, 8);
+    queue.flush(ft);
+
+    Ok(())
+}
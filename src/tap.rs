@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::collections::VecDeque;
+
+use ftdi_mpsse::MpsseCmdBuilder;
+
+use crate::queue::Queue;
+
+/// The 16 states of the IEEE 1149.1 TAP controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TapState {
+    TestLogicReset,
+    RunTestIdle,
+    SelectDrScan,
+    CaptureDr,
+    ShiftDr,
+    Exit1Dr,
+    PauseDr,
+    Exit2Dr,
+    UpdateDr,
+    SelectIrScan,
+    CaptureIr,
+    ShiftIr,
+    Exit1Ir,
+    PauseIr,
+    Exit2Ir,
+    UpdateIr,
+}
+
+use TapState::*;
+
+const ALL_STATES: [TapState; 16] = [
+    TestLogicReset, RunTestIdle,
+    SelectDrScan, CaptureDr, ShiftDr, Exit1Dr, PauseDr, Exit2Dr, UpdateDr,
+    SelectIrScan, CaptureIr, ShiftIr, Exit1Ir, PauseIr, Exit2Ir, UpdateIr,
+];
+
+//Canonical IEEE 1149.1 transition table: next state for TMS=0 and TMS=1
+fn next_state(state: TapState, tms: bool) -> TapState {
+    match (state, tms) {
+        (TestLogicReset, false) => RunTestIdle,
+        (TestLogicReset, true)  => TestLogicReset,
+
+        (RunTestIdle, false) => RunTestIdle,
+        (RunTestIdle, true)  => SelectDrScan,
+
+        (SelectDrScan, false) => CaptureDr,
+        (SelectDrScan, true)  => SelectIrScan,
+        (CaptureDr, false) => ShiftDr,
+        (CaptureDr, true)  => Exit1Dr,
+        (ShiftDr, false) => ShiftDr,
+        (ShiftDr, true)  => Exit1Dr,
+        (Exit1Dr, false) => PauseDr,
+        (Exit1Dr, true)  => UpdateDr,
+        (PauseDr, false) => PauseDr,
+        (PauseDr, true)  => Exit2Dr,
+        (Exit2Dr, false) => ShiftDr,
+        (Exit2Dr, true)  => UpdateDr,
+        (UpdateDr, false) => RunTestIdle,
+        (UpdateDr, true)  => SelectDrScan,
+
+        (SelectIrScan, false) => CaptureIr,
+        (SelectIrScan, true)  => TestLogicReset,
+        (CaptureIr, false) => ShiftIr,
+        (CaptureIr, true)  => Exit1Ir,
+        (ShiftIr, false) => ShiftIr,
+        (ShiftIr, true)  => Exit1Ir,
+        (Exit1Ir, false) => PauseIr,
+        (Exit1Ir, true)  => UpdateIr,
+        (PauseIr, false) => PauseIr,
+        (PauseIr, true)  => Exit2Ir,
+        (Exit2Ir, false) => ShiftIr,
+        (Exit2Ir, true)  => UpdateIr,
+        (UpdateIr, false) => RunTestIdle,
+        (UpdateIr, true)  => SelectDrScan,
+    }
+}
+
+//A TMS path: `len` bits of `bits`, TMS value for step `i` is bit `i` (LSB first)
+#[derive(Debug, Clone, Copy)]
+struct TmsPath {
+    bits: u8,
+    len: u8,
+}
+
+//5 clocks with TMS=1 resets the TAP from any state, regardless of where it
+//actually is - this is the failsafe path IEEE 1149.1 guarantees.
+const RESET_PATH: TmsPath = TmsPath { bits: 0x1f, len: 5 };
+
+/// Tracks the TAP controller's current state and drives it to any other
+/// state via the shortest TMS sequence, precomputed over the 16-state graph.
+pub struct Tap {
+    current_state: TapState,
+    paths: HashMap<(TapState, TapState), TmsPath>,
+}
+
+impl Tap {
+    pub fn new() -> Self {
+        let mut paths = HashMap::new();
+
+        //BFS from every state to find the shortest TMS path to every other state
+        for &from in ALL_STATES.iter() {
+            let mut visited = HashMap::new();
+            visited.insert(from, TmsPath { bits: 0, len: 0 });
+
+            let mut queue = VecDeque::new();
+            queue.push_back(from);
+
+            while let Some(state) = queue.pop_front() {
+                let path = visited[&state];
+
+                for &tms in &[false, true] {
+                    let next = next_state(state, tms);
+
+                    if let std::collections::hash_map::Entry::Vacant(e) = visited.entry(next) {
+                        let bit = if tms { 1 << path.len } else { 0 };
+                        e.insert(TmsPath { bits: path.bits | bit, len: path.len + 1 });
+                        queue.push_back(next);
+                    }
+                }
+            }
+
+            for (&to, &path) in visited.iter() {
+                paths.insert((from, to), path);
+            }
+        }
+
+        Tap { current_state: TestLogicReset, paths }
+    }
+
+    pub fn current_state(&self) -> TapState {
+        self.current_state
+    }
+
+    /// Record a state reached by a caller that clocked its own TMS bits
+    /// directly (e.g. the final bit of an IR/DR shift).
+    pub(crate) fn set_state(&mut self, state: TapState) {
+        self.current_state = state;
+    }
+
+    /// Queue the TMS sequence to drive the TAP controller to `target`,
+    /// chunked into groups of at most 7 bits (the MPSSE `clock_tms_out`
+    /// limit). Callers flush `queue` once they need the commands sent.
+    pub fn move_to(&mut self, queue: &mut Queue, target: TapState) {
+        if self.current_state == target {
+            return;
+        }
+
+        if target == TestLogicReset {
+            self.clock_tms(queue, RESET_PATH);
+            self.current_state = TestLogicReset;
+            return;
+        }
+
+        let path = self.paths[&(self.current_state, target)];
+        self.clock_tms(queue, path);
+        self.current_state = target;
+    }
+
+    fn clock_tms(&self, queue: &mut Queue, path: TmsPath) {
+        let mut bits = path.bits;
+        let mut remaining = path.len;
+
+        while remaining > 0 {
+            let chunk_len = remaining.min(7);
+            let chunk_bits = bits & ((1 << chunk_len) - 1);
+
+            let cmd = MpsseCmdBuilder::new()
+                .clock_tms_out(ftdi_mpsse::ClockTMSOut::NegEdge, chunk_bits, false, chunk_len);
+            queue.queue(&cmd, 0);
+
+            bits >>= chunk_len;
+            remaining -= chunk_len;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_path_run_test_idle_to_shift_dr() {
+        let tap = Tap::new();
+        let path = tap.paths[&(RunTestIdle, ShiftDr)];
+        //TMS 1,0,0: SelectDrScan -> CaptureDr -> ShiftDr
+        assert_eq!(path.len, 3);
+        assert_eq!(path.bits, 0b001);
+    }
+
+    #[test]
+    fn shortest_path_to_self_is_empty() {
+        let tap = Tap::new();
+        let path = tap.paths[&(ShiftIr, ShiftIr)];
+        assert_eq!(path.len, 0);
+    }
+
+    #[test]
+    fn move_to_same_state_emits_nothing() {
+        let mut tap = Tap::new();
+        let mut queue = Queue::new();
+
+        //Tap::new() starts in TestLogicReset already
+        tap.move_to(&mut queue, TestLogicReset);
+        assert_eq!(queue.queued_len(), 0);
+        assert_eq!(tap.current_state(), TestLogicReset);
+    }
+
+    #[test]
+    fn move_to_updates_current_state() {
+        let mut tap = Tap::new();
+        let mut queue = Queue::new();
+
+        tap.move_to(&mut queue, ShiftDr);
+        assert_eq!(tap.current_state(), ShiftDr);
+        assert!(queue.queued_len() > 0);
+    }
+}